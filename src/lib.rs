@@ -1,24 +1,96 @@
 use actix::{Actor, Addr};
 use actix_web::middleware::Logger;
-use actix_web::{dev::Server, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    dev::Server, http::header, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 use actix_web_actors::ws;
+use futures_util::future::{select, Either};
 use std::io::Write;
-use std::{net::TcpListener, time::Instant};
+use std::{
+    net::TcpListener,
+    time::{Duration, Instant},
+};
 
 mod server;
 mod session;
 
+/// How long sessions get to receive their close frame before the server stops
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Access log format, same as actix's default
+const ACCESS_LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
+
+/// Access log format with the client address replaced by `-`
+const REDACTED_ACCESS_LOG_FORMAT: &str = r#"- "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
+
+/// Runtime options for the server, see `run_with`
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// Close websocket connections that send no text/binary messages for this
+    /// long, heartbeats don't count. Disabled by default.
+    pub idle_timeout: Option<Duration>,
+
+    /// Bearer token required by the admin endpoints, they are disabled when
+    /// this is `None`
+    pub admin_token: Option<String>,
+
+    /// Include the client's IP address in the access log. Off by default so
+    /// addresses aren't stored unless someone asks for them.
+    pub log_remote_address: bool,
+}
+
 // basic health check end_point
 async fn health_check() -> impl Responder {
     log::info!("Request made to the health_check endroute");
     HttpResponse::Ok().finish()
 }
 
+// compare secrets without stopping at the first differing byte, so response
+// times don't give away how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// push an operational notice to every connected session, admin only
+async fn announce(
+    req: HttpRequest,
+    text: String,
+    srv: web::Data<Addr<server::CalServer>>,
+    settings: web::Data<Settings>,
+) -> HttpResponse {
+    let token = match &settings.admin_token {
+        Some(token) => token,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+
+    if !authorized {
+        log::warn!("Unauthorized request made to the announce endroute");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    log::info!("Announcing to all sessions: {}", text);
+    srv.do_send(server::Announce { text });
+
+    HttpResponse::Ok().finish()
+}
+
 // entry point to the webscoket connection
 async fn ws_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<server::CalServer>>,
+    settings: web::Data<Settings>,
 ) -> Result<HttpResponse, Error> {
     // start the web socket server here
     log::info!("Request made to the websocket endroute");
@@ -31,6 +103,9 @@ async fn ws_route(
         session::WsCalSession {
             id: 0,
             hb: Instant::now(),
+            idle: Instant::now(),
+            idle_timeout: settings.idle_timeout,
+            cal: None,
             addr: srv.get_ref().clone(),
         },
         &req,
@@ -38,20 +113,93 @@ async fn ws_route(
     )
 }
 
-// return an instance of our server
+// resolves on Ctrl-C, if the handler can't be installed it never resolves so
+// the server isn't shut down by accident
+async fn ctrl_c() {
+    if let Err(e) = actix_rt::signal::ctrl_c().await {
+        log::error!("Unable to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+// resolves on Ctrl-C, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                let term = async move {
+                    if term.recv().await.is_none() {
+                        log::error!("SIGTERM listener closed");
+                        std::future::pending::<()>().await;
+                    }
+                };
+
+                select(Box::pin(ctrl_c()), Box::pin(term)).await;
+                return;
+            }
+            Err(e) => log::error!("Unable to listen for SIGTERM: {}", e),
+        }
+    }
+
+    ctrl_c().await;
+}
+
+// return an instance of our server with the default settings
 pub fn run(listener: TcpListener) -> Result<Server, std::io::Error> {
+    run_with(listener, Settings::default())
+}
+
+// return an instance of our server
+pub fn run_with(listener: TcpListener, settings: Settings) -> Result<Server, std::io::Error> {
     // start calendar server
-    let server = server::CalServer::new().start();
+    let cal_server = server::CalServer::new().start();
+    let addr = cal_server.clone();
+
+    let log_format = if settings.log_remote_address {
+        ACCESS_LOG_FORMAT
+    } else {
+        REDACTED_ACCESS_LOG_FORMAT
+    };
 
+    // signals are handled below so sessions can be closed before the server stops
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(Logger::default())
-            .app_data(web::Data::new(server.clone()))
+            .wrap(Logger::new(log_format))
+            .app_data(web::Data::new(addr.clone()))
+            .app_data(web::Data::new(settings.clone()))
             .route("/health_check", web::get().to(health_check))
+            .route("/announce", web::post().to(announce))
             .route("/ws", web::get().to(ws_route))
     })
+    .disable_signals()
     .listen(listener)?
     .run();
 
+    // tokio's handlers replace the default ones for the whole process, so a
+    // second signal forces the shutdown instead of killing the process
+    let handle = server.handle();
+    actix_rt::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutdown signal recieved, closing websocket sessions");
+
+        let graceful = {
+            let handle = handle.clone();
+            async move {
+                let _ = cal_server.send(server::ShutdownAll).await;
+                actix_rt::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+
+                handle.stop(true).await;
+            }
+        };
+
+        if let Either::Right(_) = select(Box::pin(graceful), Box::pin(shutdown_signal())).await {
+            log::warn!("Second shutdown signal recieved, stopping immediately");
+            handle.stop(false).await;
+        }
+    });
+
     Ok(server)
 }
@@ -1,7 +1,7 @@
 use env_logger::Env;
 use std::net::TcpListener;
 
-use opencal::run;
+use opencal::{run_with, Settings};
 
 // hello to all reading this, I am currently daf and vibing super hard with sebas ╰⋃╯
 
@@ -14,5 +14,15 @@ async fn main() -> std::io::Result<()> {
 
     let listenser = TcpListener::bind("127.0.0.1:8000")?;
 
-    run(listenser)?.await
+    let settings = Settings {
+        // /announce stays disabled unless a token is configured
+        admin_token: std::env::var("OPENCAL_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty()),
+        log_remote_address: std::env::var("OPENCAL_LOG_REMOTE_ADDRESS")
+            .is_ok_and(|value| value == "1" || value == "true"),
+        ..Default::default()
+    };
+
+    run_with(listenser, settings)?.await
 }
@@ -11,11 +11,29 @@ use std::collections::{HashMap, HashSet};
 #[rtype(result = "()")]
 pub struct Message(pub String);
 
+/// Ask a calendar session to close its connection
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Close {
+    /// Reason sent to the client in the close frame
+    pub reason: String,
+}
+
+/// Calendar the session joined has been deleted
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct CalDeleted {
+    /// Calendar name
+    pub name: String,
+}
+
 /// New cal session is created
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    pub close: Recipient<Close>,
+    pub deleted: Recipient<CalDeleted>,
 }
 
 /// Session is disconnected
@@ -26,6 +44,33 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// Join calendar, the session will receive messages sent to it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Join {
+    /// Client id
+    pub id: usize,
+    /// Calendar name
+    pub name: String,
+}
+
+/// Delete a calendar, every session in it is removed and notified
+#[derive(Message)]
+#[rtype(result = "Result<(), CalError>")]
+pub struct DeleteCal {
+    /// Id of the client session asking for the delete
+    pub id: usize,
+    /// Calendar name
+    pub name: String,
+}
+
+/// Errors returned to sessions by the calendar server
+#[derive(Debug, thiserror::Error)]
+pub enum CalError {
+    #[error("calendar {0} does not exist")]
+    CalNotFound(String),
+}
+
 /// Send message to specific calendar
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -38,11 +83,31 @@ pub struct ClientMessage {
     pub cal: String,
 }
 
+/// Send message to every connected session, regardless of calendar.
+/// Meant for operational notices, only sent from the admin `/announce` route.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Announce {
+    pub text: String,
+}
+
+/// Server is going down, close every session
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ShutdownAll;
+
+/// How the server reaches a connected session
+struct SessionHandle {
+    addr: Recipient<Message>,
+    close: Recipient<Close>,
+    deleted: Recipient<CalDeleted>,
+}
+
 /// Struct representing the Websocket server
 /// Responsible for coordinating calendars
 pub struct CalServer {
-    sessions: HashMap<usize, Recipient<Message>>,
-    _cals: HashMap<String, HashSet<usize>>,
+    sessions: HashMap<usize, SessionHandle>,
+    cals: HashMap<String, HashSet<usize>>,
     rng: ThreadRng,
 }
 
@@ -50,7 +115,7 @@ impl CalServer {
     pub fn new() -> Self {
         Self {
             sessions: HashMap::new(),
-            _cals: HashMap::new(),
+            cals: HashMap::new(),
             rng: rand::thread_rng(),
         }
     }
@@ -58,17 +123,26 @@ impl CalServer {
 
 impl CalServer {
     /// Send message to all users in the calendar
-    fn _send_message(&self, cal: &str, message: &str, skip_id: usize) {
-        if let Some(sessions) = self._cals.get(cal) {
+    fn send_message(&self, cal: &str, message: &str, skip_id: usize) {
+        if let Some(sessions) = self.cals.get(cal) {
             for id in sessions {
                 if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        addr.do_send(Message(message.to_owned()));
+                    if let Some(session) = self.sessions.get(id) {
+                        session.addr.do_send(Message(message.to_owned()));
                     }
                 }
             }
         }
     }
+
+    /// Remove a session from every calendar, calendars left empty are dropped
+    /// so the map doesn't grow with every name ever joined
+    fn leave_all(&mut self, id: usize) {
+        self.cals.retain(|_, sessions| {
+            sessions.remove(&id);
+            !sessions.is_empty()
+        });
+    }
 }
 
 /// Make actor from `ChatServer`
@@ -83,9 +157,22 @@ impl Handler<Connect> for CalServer {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) -> Self::Result {
-        // assign an id to the session and store it in the hashmap
-        let id = self.rng.gen();
-        self.sessions.insert(id, msg.addr);
+        // assign an id to the session and store it in the hashmap, 0 is
+        // reserved for sessions that haven't been registered yet
+        let id = loop {
+            let id = self.rng.gen();
+            if id != 0 && !self.sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        self.sessions.insert(
+            id,
+            SessionHandle {
+                addr: msg.addr,
+                close: msg.close,
+                deleted: msg.deleted,
+            },
+        );
 
         println!("Connection established\nSession id: {}...", id);
 
@@ -99,6 +186,180 @@ impl Handler<Disconnect> for CalServer {
     fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) -> Self::Result {
         println!("Session {} has disconnected", msg.id);
 
-        self.sessions.remove(&msg.id);
+        // remove the session from every calendar it joined
+        if self.sessions.remove(&msg.id).is_some() {
+            self.leave_all(msg.id);
+        }
+    }
+}
+
+// A session can only be in one calendar at a time, joining a new one
+// leaves the previous
+impl Handler<Join> for CalServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _ctx: &mut Self::Context) -> Self::Result {
+        let Join { id, name } = msg;
+
+        self.leave_all(id);
+        self.cals.entry(name).or_default().insert(id);
+    }
+}
+
+/// Handler for messages sent by a session, the sender already knows what it
+/// sent so it is skipped when broadcasting
+impl Handler<ClientMessage> for CalServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientMessage, _ctx: &mut Self::Context) -> Self::Result {
+        // only members may post to a calendar
+        let is_member = self
+            .cals
+            .get(&msg.cal)
+            .is_some_and(|sessions| sessions.contains(&msg.id));
+
+        if is_member {
+            self.send_message(&msg.cal, msg.msg.as_str(), msg.id);
+        }
+    }
+}
+
+impl Handler<Announce> for CalServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Announce, _ctx: &mut Self::Context) -> Self::Result {
+        for session in self.sessions.values() {
+            session
+                .addr
+                .do_send(Message(format!("/announce {}", msg.text)));
+        }
+    }
+}
+
+impl Handler<DeleteCal> for CalServer {
+    type Result = Result<(), CalError>;
+
+    fn handle(&mut self, msg: DeleteCal, _ctx: &mut Self::Context) -> Self::Result {
+        let sessions = self
+            .cals
+            .remove(&msg.name)
+            .ok_or_else(|| CalError::CalNotFound(msg.name.clone()))?;
+
+        // the requester gets its own reply, everyone else is told here
+        for id in sessions.iter().filter(|id| **id != msg.id) {
+            if let Some(session) = self.sessions.get(id) {
+                session.deleted.do_send(CalDeleted {
+                    name: msg.name.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<ShutdownAll> for CalServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ShutdownAll, _ctx: &mut Self::Context) -> Self::Result {
+        log::info!("Shutting down, closing {} sessions", self.sessions.len());
+
+        for session in self.sessions.values() {
+            session.close.do_send(Close {
+                reason: "server shutting down".to_owned(),
+            });
+        }
+    }
+}
+
+/// Snapshot of the server's session ids and calendar membership, for tests
+#[cfg(test)]
+#[derive(Message)]
+#[rtype(result = "(HashSet<usize>, HashMap<String, HashSet<usize>>)")]
+pub(crate) struct State;
+
+#[cfg(test)]
+impl Handler<State> for CalServer {
+    type Result = MessageResult<State>;
+
+    fn handle(&mut self, _msg: State, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult((self.sessions.keys().copied().collect(), self.cals.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stand-in for a websocket session that ignores everything sent to it
+    struct Sink;
+
+    impl Actor for Sink {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Sink {
+        type Result = ();
+        fn handle(&mut self, _msg: Message, _ctx: &mut Self::Context) {}
+    }
+
+    impl Handler<Close> for Sink {
+        type Result = ();
+        fn handle(&mut self, _msg: Close, _ctx: &mut Self::Context) {}
+    }
+
+    impl Handler<CalDeleted> for Sink {
+        type Result = ();
+        fn handle(&mut self, _msg: CalDeleted, _ctx: &mut Self::Context) {}
+    }
+
+    async fn connect(server: &Addr<CalServer>) -> usize {
+        let sink = Sink.start();
+
+        server
+            .send(Connect {
+                addr: sink.clone().recipient(),
+                close: sink.clone().recipient(),
+                deleted: sink.recipient(),
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn join(server: &Addr<CalServer>, id: usize, name: &str) {
+        let name = name.to_owned();
+        server.send(Join { id, name }).await.unwrap();
+    }
+
+    // calendars nobody is in anymore shouldn't stick around
+    #[actix_rt::test]
+    async fn empty_cals_are_pruned() {
+        let server = CalServer::new().start();
+
+        let a = connect(&server).await;
+        let b = connect(&server).await;
+
+        join(&server, a, "work").await;
+        join(&server, b, "work").await;
+        join(&server, a, "home").await;
+
+        let (_, cals) = server.send(State).await.unwrap();
+        assert_eq!(cals.len(), 2);
+        assert_eq!(cals["work"], HashSet::from([b]));
+        assert_eq!(cals["home"], HashSet::from([a]));
+
+        // b leaving empties work
+        join(&server, b, "home").await;
+        let (_, cals) = server.send(State).await.unwrap();
+        assert_eq!(cals.len(), 1);
+        assert_eq!(cals["home"], HashSet::from([a, b]));
+
+        for id in [a, b] {
+            server.send(Disconnect { id }).await.unwrap();
+        }
+
+        let (sessions, cals) = server.send(State).await.unwrap();
+        assert!(sessions.is_empty());
+        assert!(cals.is_empty());
     }
 }
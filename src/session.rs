@@ -1,3 +1,15 @@
+//! WsCalSession is the actor behind each websocket connection.
+//!
+//! Clients talk to it with text frames. Frames starting with `/` are
+//! commands (`/join <cal>`, `/delete <cal>`), anything else is forwarded to
+//! the other members of the joined calendar. Text the server generates itself
+//! also starts with `/` (`/joined <cal>`, `/deleted <cal>`, `/error <reason>`,
+//! `/announce <text>`), since peer text never does clients can always tell the
+//! two apart.
+//!
+//! A session is in at most one calendar at a time. `/join` while already in a
+//! calendar leaves the previous one, so the client stops receiving its updates.
+
 use std::time::{Duration, Instant};
 
 use actix::prelude::*;
@@ -13,13 +25,25 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct WsCalSession {
-    /// unique session id
+    /// unique session id, 0 until the server has registered the session
     pub id: usize,
 
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
     pub hb: Instant,
 
+    /// Last time the client sent an application (text/binary) message
+    pub idle: Instant,
+
+    /// How long the client may go without sending an application message
+    /// before the connection is closed with `CloseCode::Normal` and the reason
+    /// "idle timeout". Pings and pongs do not count as activity, `None`
+    /// disables the check.
+    pub idle_timeout: Option<Duration>,
+
+    /// Calendar the session has joined, if any
+    pub cal: Option<String>,
+
     /// Chat server
     pub addr: Addr<server::CalServer>,
 }
@@ -35,10 +59,7 @@ impl WsCalSession {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
-                // notify chat server
-                act.addr.do_send(server::Disconnect { id: act.id });
-
-                // stop actor
+                // stop actor, the chat server is notified when the session is dropped
                 ctx.stop();
 
                 // don't try to send a ping
@@ -48,6 +69,30 @@ impl WsCalSession {
             ctx.ping(b"");
         });
     }
+
+    /// helper method that closes the connection once the client has sent no
+    /// application message for `timeout`.
+    ///
+    /// the check is scheduled for when the timeout would run out and pushed
+    /// back if the client was active in the meantime
+    fn idle_check(&self, ctx: &mut ws::WebsocketContext<Self>, timeout: Duration) {
+        let remaining = timeout.saturating_sub(self.idle.elapsed());
+
+        ctx.run_later(remaining, move |act, ctx| {
+            if act.idle.elapsed() < timeout {
+                act.idle_check(ctx, timeout);
+                return;
+            }
+
+            println!("Websocket Client idle for too long, disconnecting!");
+
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Normal,
+                description: Some("idle timeout".to_owned()),
+            }));
+            ctx.stop();
+        });
+    }
 }
 
 // WsChatSession is a "middle man" between the server and the client.
@@ -61,6 +106,10 @@ impl Actor for WsCalSession {
         // we'll start heartbeat process on session start.
         self.hb(ctx);
 
+        if let Some(timeout) = self.idle_timeout {
+            self.idle_check(ctx, timeout);
+        }
+
         // register self in chat server. `AsyncContext::wait` register
         // future within context, but context waits until this future resolves
         // before processing any other events.
@@ -70,7 +119,9 @@ impl Actor for WsCalSession {
         // send Connect message to ChatServer
         self.addr
             .send(server::Connect {
-                addr: addr.recipient(),
+                addr: addr.clone().recipient(),
+                close: addr.clone().recipient(),
+                deleted: addr.recipient(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -83,11 +134,22 @@ impl Actor for WsCalSession {
             })
             .wait(ctx);
     }
+}
+
+/// Notify the chat server when the session goes away. Doing this on drop rather
+/// than in `stopping` means the session is still removed from the server if the
+/// actor is torn down abnormally (e.g. unwinding from a panic).
+impl Drop for WsCalSession {
+    fn drop(&mut self) {
+        // never registered (e.g. the handshake failed), nothing to clean up
+        if self.id == 0 {
+            return;
+        }
 
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        // notify chat server
+        log::info!("Session {} torn down", self.id);
+
+        // best-effort, the server may already be gone
         self.addr.do_send(server::Disconnect { id: self.id });
-        Running::Stop
     }
 }
 
@@ -96,7 +158,35 @@ impl Handler<server::Message> for WsCalSession {
     type Result = ();
 
     /// if we recieve a server::Message from ChatServer then forward it over to the client
-    fn handle(&mut self, _msg: server::Message, _ctx: &mut Self::Context) {}
+    fn handle(&mut self, msg: server::Message, ctx: &mut Self::Context) {
+        ctx.text(msg.0);
+    }
+}
+
+/// Server asked us to go away, let the client know why before stopping
+impl Handler<server::Close> for WsCalSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Close, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Away,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
+    }
+}
+
+/// A calendar we were in is gone, forget it and let the client know
+impl Handler<server::CalDeleted> for WsCalSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::CalDeleted, ctx: &mut Self::Context) {
+        if self.cal.as_ref() == Some(&msg.name) {
+            self.cal = None;
+        }
+
+        ctx.text(format!("/deleted {}", msg.name));
+    }
 }
 
 /// WebSocket message handler
@@ -119,8 +209,75 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsCalSession {
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(text) => println!("Text recieved: {}", text),
-            ws::Message::Binary(_) => println!("Unexpected binary"),
+            ws::Message::Text(text) => {
+                self.idle = Instant::now();
+                let m = text.trim();
+
+                if let Some(name) = m.strip_prefix("/join ") {
+                    let name = name.trim().to_owned();
+
+                    // acknowledge once the server has registered us so the
+                    // client knows it will start receiving updates
+                    self.addr
+                        .send(server::Join {
+                            id: self.id,
+                            name: name.clone(),
+                        })
+                        .into_actor(self)
+                        .then(|res, act, ctx| {
+                            match res {
+                                Ok(_) => {
+                                    ctx.text(format!("/joined {}", name));
+                                    act.cal = Some(name);
+                                }
+                                // something is wrong with chat server
+                                _ => ctx.stop(),
+                            }
+                            fut::ready(())
+                        })
+                        .wait(ctx);
+                } else if let Some(name) = m.strip_prefix("/delete ") {
+                    let name = name.trim().to_owned();
+
+                    self.addr
+                        .send(server::DeleteCal {
+                            id: self.id,
+                            name: name.clone(),
+                        })
+                        .into_actor(self)
+                        .then(move |res, act, ctx| {
+                            match res {
+                                Ok(Ok(())) => {
+                                    ctx.text(format!("/deleted {}", name));
+                                    if act.cal.as_ref() == Some(&name) {
+                                        act.cal = None;
+                                    }
+                                }
+                                Ok(Err(e)) => ctx.text(format!("/error {}", e)),
+                                // something is wrong with chat server
+                                _ => ctx.stop(),
+                            }
+                            fut::ready(())
+                        })
+                        .wait(ctx);
+                } else if m.starts_with('/') {
+                    // never forwarded, that would let peers fake server notices
+                    ctx.text(format!("/error unknown command {}", m));
+                } else if let Some(cal) = &self.cal {
+                    // forward to everyone else in the calendar
+                    self.addr.do_send(server::ClientMessage {
+                        id: self.id,
+                        msg: m.to_owned(),
+                        cal: cal.clone(),
+                    });
+                } else {
+                    println!("Text recieved: {}", text)
+                }
+            }
+            ws::Message::Binary(_) => {
+                self.idle = Instant::now();
+                println!("Unexpected binary")
+            }
             ws::Message::Close(reason) => {
                 ctx.close(reason);
                 ctx.stop();
@@ -132,3 +289,114 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsCalSession {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{CalServer, State};
+    use actix_rt::task::JoinHandle;
+    use actix_web::{error::PayloadError, web::Bytes};
+    use futures_util::{stream, StreamExt};
+
+    /// Start a session for a client that never sends anything. The returned
+    /// task drives the session and collects every frame it writes until it stops.
+    fn start_session(server: &Addr<CalServer>) -> JoinHandle<Vec<u8>> {
+        let session = WsCalSession {
+            id: 0,
+            hb: Instant::now(),
+            idle: Instant::now(),
+            idle_timeout: None,
+            cal: None,
+            addr: server.clone(),
+        };
+
+        let payload = stream::pending::<Result<Bytes, PayloadError>>();
+        let frames = ws::WebsocketContext::create(session, payload);
+
+        actix_rt::spawn(frames.fold(Vec::new(), |mut out, frame| async move {
+            out.extend_from_slice(&frame.unwrap());
+            out
+        }))
+    }
+
+    /// Wait until the server has registered `n` sessions and return their ids
+    async fn registered(server: &Addr<CalServer>, n: usize) -> Vec<usize> {
+        loop {
+            let (sessions, _) = server.send(State).await.unwrap();
+            if sessions.len() == n {
+                return sessions.into_iter().collect();
+            }
+            actix_rt::task::yield_now().await;
+        }
+    }
+
+    /// Pull the close code and reason out of the frames a session wrote, server
+    /// frames are unmasked and the ones sent here are all short
+    fn close_frame(mut frames: &[u8]) -> Option<(u16, String)> {
+        while frames.len() >= 2 {
+            let opcode = frames[0] & 0x0f;
+            let len = (frames[1] & 0x7f) as usize;
+            let payload = &frames[2..2 + len];
+
+            if opcode == 0x8 {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                let reason = String::from_utf8(payload[2..].to_vec()).unwrap();
+                return Some((code, reason));
+            }
+
+            frames = &frames[2 + len..];
+        }
+
+        None
+    }
+
+    // every session gets a close frame saying why when the server shuts down
+    #[actix_rt::test]
+    async fn shutdown_closes_every_session() {
+        let server = CalServer::new().start();
+
+        let tasks = [start_session(&server), start_session(&server)];
+        registered(&server, 2).await;
+
+        server.send(server::ShutdownAll).await.unwrap();
+
+        for task in tasks {
+            // the frame stream ends once the session has stopped
+            let frames = task.await.unwrap();
+            let (code, reason) = close_frame(&frames).expect("no close frame sent");
+
+            assert_eq!(ws::CloseCode::from(code), ws::CloseCode::Away);
+            assert_eq!(reason, "server shutting down");
+        }
+
+        let (sessions, cals) = server.send(State).await.unwrap();
+        assert!(sessions.is_empty());
+        assert!(cals.is_empty());
+    }
+
+    // a session torn down without going through `stopping` is still pruned
+    #[actix_rt::test]
+    async fn dropped_session_is_pruned() {
+        let server = CalServer::new().start();
+
+        let task = start_session(&server);
+        let id = registered(&server, 1).await[0];
+
+        let name = "work".to_owned();
+        server.send(server::Join { id, name }).await.unwrap();
+
+        // dropping the task drops the actor without stopping it
+        task.abort();
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            let (sessions, cals) = server.send(State).await.unwrap();
+            if sessions.is_empty() && cals.is_empty() {
+                break;
+            }
+
+            assert!(Instant::now() < deadline, "session was never pruned");
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
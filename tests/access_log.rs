@@ -0,0 +1,78 @@
+mod common;
+
+use common::spawn_app_with;
+use opencal::Settings;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Access log lines written by actix's logger middleware
+static ACCESS_LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Logger that keeps the access log lines around so tests can look at them
+struct Capture;
+
+impl log::Log for Capture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata
+            .target()
+            .starts_with("actix_web::middleware::logger")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            ACCESS_LOG.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// make a request and wait for its access log line, the query makes it unique
+async fn logged_request(addr: &str, query: &str) -> String {
+    let resp = reqwest::get(&format!("http://{}/health_check?{}", addr, query))
+        .await
+        .expect("Failed to send request to server");
+    assert!(resp.status().is_success());
+
+    // the line is written once the response is done, which can be after the
+    // client has it
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        let line = ACCESS_LOG
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|line| line.contains(query))
+            .cloned();
+
+        if let Some(line) = line {
+            return line;
+        }
+
+        assert!(
+            Instant::now() < deadline,
+            "no access log line for {}",
+            query
+        );
+        actix_rt::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// the client address only shows up in the access log when it's been asked for,
+// both cases share one test since the logger is global to the process
+#[actix_rt::test]
+async fn access_log_remote_address_toggle() {
+    log::set_boxed_logger(Box::new(Capture)).expect("Failed to install logger");
+    log::set_max_level(log::LevelFilter::Info);
+
+    let addr = spawn_app_with(Settings {
+        log_remote_address: true,
+        ..Default::default()
+    });
+    let line = logged_request(&addr, "case=enabled").await;
+    assert!(line.contains("127.0.0.1"), "address missing: {}", line);
+
+    let addr = spawn_app_with(Settings::default());
+    let line = logged_request(&addr, "case=disabled").await;
+    assert!(!line.contains("127.0.0.1"), "address logged: {}", line);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{connect, next_text, send_text, spawn_app_with};
+use opencal::Settings;
+
+// an admin announcement reaches every connected session, whatever calendar it is in
+#[actix_rt::test]
+async fn announce_reaches_every_session() {
+    let addr = spawn_app_with(Settings {
+        admin_token: Some("hunter2".to_owned()),
+        ..Default::default()
+    });
+
+    println!("Connecting to: {}", addr);
+
+    let mut work = connect(&addr).await;
+    let mut home = connect(&addr).await;
+
+    // wait for both sessions to be registered with the server
+    send_text(&mut work, "/join work").await;
+    assert_eq!(next_text(&mut work).await, "/joined work");
+    send_text(&mut home, "/join home").await;
+    assert_eq!(next_text(&mut home).await, "/joined home");
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/announce", &addr);
+
+    // only the admin may announce
+    for token in [None, Some("wrong"), Some("hunter3"), Some("hunter")] {
+        let mut req = client.post(&url).body("not from an admin");
+        if let Some(token) = token {
+            req = req.bearer_auth(token);
+        }
+
+        let resp = req.send().await.expect("Failed to send request to server");
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    let resp = client
+        .post(&url)
+        .bearer_auth("hunter2")
+        .body("maintenance at 5pm")
+        .send()
+        .await
+        .expect("Failed to send request to server");
+    assert!(resp.status().is_success());
+
+    // the rejected announcements never made it, so this is the first thing both see
+    for ws_stream in [&mut work, &mut home] {
+        assert_eq!(next_text(ws_stream).await, "/announce maintenance at 5pm");
+    }
+}
+
+// without an admin token configured the endpoint doesn't exist
+#[actix_rt::test]
+async fn announce_disabled_without_admin_token() {
+    let addr = common::spawn_app();
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}/announce", &addr))
+        .bearer_auth("")
+        .body("hello")
+        .send()
+        .await
+        .expect("Failed to send request to server");
+
+    assert_eq!(resp.status().as_u16(), 404);
+}
@@ -0,0 +1,67 @@
+// helpers shared by the websocket integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use futures_util::{SinkExt, StreamExt};
+use opencal::Settings;
+use std::net::TcpListener;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
+};
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub async fn connect(addr: &str) -> WsStream {
+    let (ws_stream, _) = connect_async(&format!("ws://{}/ws", addr))
+        .await
+        .expect("Failed to connect...");
+
+    ws_stream
+}
+
+pub async fn send_text(ws_stream: &mut WsStream, text: &str) {
+    ws_stream
+        .send(Message::Text(text.to_owned()))
+        .await
+        .expect("Message to WS failed to send...");
+}
+
+// wait for the next text message, skipping over heartbeats
+pub async fn next_text(ws_stream: &mut WsStream) -> String {
+    while let Some(message) = ws_stream.next().await {
+        match message.unwrap() {
+            Message::Text(text) => return text,
+            Message::Ping(_) | Message::Pong(_) => continue,
+            m => panic!("Unexpected message: {:?}", m),
+        }
+    }
+
+    panic!("Websocket closed before a text message was recieved");
+}
+
+// make sure nothing is pushed to the client for a little while
+pub async fn assert_no_text(ws_stream: &mut WsStream) {
+    let res = actix_rt::time::timeout(Duration::from_millis(500), next_text(ws_stream)).await;
+
+    if let Ok(text) = res {
+        panic!("Unexpected text recieved: {}", text);
+    }
+}
+
+pub fn spawn_app() -> String {
+    spawn_app_with(Settings::default())
+}
+
+pub fn spawn_app_with(settings: Settings) -> String {
+    // use port 0 to make the OS pick a random port that isnt being used
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind to address");
+    let port = listener.local_addr().unwrap().port();
+
+    let server = opencal::run_with(listener, settings).expect("Failed to bind address");
+
+    tokio::spawn(server);
+
+    // return address of server
+    format!("127.0.0.1:{}", port)
+}
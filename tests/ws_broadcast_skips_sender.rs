@@ -0,0 +1,28 @@
+mod common;
+
+use common::{assert_no_text, connect, next_text, send_text, spawn_app};
+
+// a message sent to a calendar should reach the other sessions in it, but not the sender
+#[actix_rt::test]
+async fn ws_cal_broadcast_skips_sender() {
+    let addr = spawn_app();
+
+    println!("Connecting to: {}", addr);
+
+    let mut sender = connect(&addr).await;
+    let mut peer = connect(&addr).await;
+
+    // both clients join the same calendar and wait for the server to acknowledge it
+    for ws_stream in [&mut sender, &mut peer] {
+        send_text(ws_stream, "/join work").await;
+        assert_eq!(next_text(ws_stream).await, "/joined work");
+    }
+
+    send_text(&mut sender, "new event").await;
+
+    // the peer should be pushed the message
+    assert_eq!(next_text(&mut peer).await, "new event");
+
+    // the sender already knows what it sent so it shouldn't get anything back
+    assert_no_text(&mut sender).await;
+}
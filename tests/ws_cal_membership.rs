@@ -0,0 +1,38 @@
+mod common;
+
+use common::{assert_no_text, connect, next_text, send_text, spawn_app};
+
+// joining a calendar leaves the previous one, updates only come from the current one
+#[actix_rt::test]
+async fn ws_join_switches_calendar() {
+    let addr = spawn_app();
+
+    println!("Connecting to: {}", addr);
+
+    let mut client = connect(&addr).await;
+    let mut work = connect(&addr).await;
+    let mut home = connect(&addr).await;
+
+    for (ws_stream, cal) in [
+        (&mut client, "work"),
+        (&mut work, "work"),
+        (&mut home, "home"),
+    ] {
+        send_text(ws_stream, &format!("/join {}", cal)).await;
+        assert_eq!(next_text(ws_stream).await, format!("/joined {}", cal));
+    }
+
+    send_text(&mut work, "work event").await;
+    assert_eq!(next_text(&mut client).await, "work event");
+
+    send_text(&mut client, "/join home").await;
+    assert_eq!(next_text(&mut client).await, "/joined home");
+
+    // the old calendar no longer reaches the client
+    send_text(&mut work, "another work event").await;
+    assert_no_text(&mut client).await;
+
+    // the new one does
+    send_text(&mut home, "home event").await;
+    assert_eq!(next_text(&mut client).await, "home event");
+}
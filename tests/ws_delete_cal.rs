@@ -0,0 +1,47 @@
+mod common;
+
+use common::{assert_no_text, connect, next_text, send_text, spawn_app};
+
+// deleting a calendar notifies its other members, deleting it again is an error
+#[actix_rt::test]
+async fn ws_delete_cal_works() {
+    let addr = spawn_app();
+
+    println!("Connecting to: {}", addr);
+
+    let mut owner = connect(&addr).await;
+    let mut peer = connect(&addr).await;
+
+    for ws_stream in [&mut owner, &mut peer] {
+        send_text(ws_stream, "/join work").await;
+        assert_eq!(next_text(ws_stream).await, "/joined work");
+    }
+
+    // peers can't fake a server notice
+    send_text(&mut peer, "/deleted work").await;
+    assert_eq!(
+        next_text(&mut peer).await,
+        "/error unknown command /deleted work"
+    );
+    assert_no_text(&mut owner).await;
+
+    send_text(&mut owner, "/delete work").await;
+    assert_eq!(next_text(&mut owner).await, "/deleted work");
+
+    // the peer is told so it can navigate away
+    assert_eq!(next_text(&mut peer).await, "/deleted work");
+
+    // the calendar is gone now
+    send_text(&mut owner, "/delete work").await;
+    assert_eq!(
+        next_text(&mut owner).await,
+        "/error calendar work does not exist"
+    );
+
+    // a new calendar with the same name shouldn't hear from the evicted peer
+    send_text(&mut owner, "/join work").await;
+    assert_eq!(next_text(&mut owner).await, "/joined work");
+
+    send_text(&mut peer, "still here?").await;
+    assert_no_text(&mut owner).await;
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::{connect, spawn_app_with};
+use futures_util::StreamExt;
+use opencal::Settings;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, Message};
+
+// a client that only answers heartbeats should be closed once the idle timeout passes
+#[actix_rt::test]
+async fn ws_idle_timeout_closes_connection() {
+    // longer than one heartbeat interval so we see pings before the close
+    let addr = spawn_app_with(Settings {
+        idle_timeout: Some(Duration::from_secs(6)),
+        ..Default::default()
+    });
+
+    println!("Connecting to: {}", addr);
+
+    let mut ws_stream = connect(&addr).await;
+    let connected = Instant::now();
+
+    // reading the stream answers the server's pings for us
+    let mut pings = 0;
+    while let Some(message) = ws_stream.next().await {
+        match message.unwrap() {
+            Message::Ping(_) => pings += 1,
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Normal);
+                assert_eq!(frame.reason, "idle timeout");
+                assert!(pings > 0, "connection closed before any heartbeat");
+
+                // closed when the timeout runs out, not on the next heartbeat
+                assert!(connected.elapsed() < Duration::from_secs(8));
+                return;
+            }
+            m => panic!("Unexpected message: {:?}", m),
+        }
+    }
+
+    panic!("Websocket closed without a close frame");
+}